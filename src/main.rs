@@ -1,39 +1,208 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::iter::Peekable;
 use std::str::Chars;
 use std::vec::IntoIter;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 enum Token {
     Int(i64),
+    Float(f64),
+    Ident(String),
     Add,
+    Sub,
     Mul,
+    Div,
+    Mod,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Assign,
     LParen,
     RParen,
 }
 
+/// A `Token` paired with the character offset it started at, used to locate
+/// the source of an `EvalError`.
+#[derive(Clone, Debug, PartialEq)]
+struct TokenAt {
+    token: Token,
+    pos: usize,
+}
+
+/// Maps variable names to the last `Value` assigned to them. Threaded
+/// through evaluation so `x = 2 + 3` followed by `x * 4` can see `x`.
+type Environment = HashMap<String, Value>;
+
+/// The result of evaluating an expression: an integer, a float, or the
+/// `Bool` produced by a comparison. Arithmetic between an `Int` and a
+/// `Float` promotes the `Int` side to `Float`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// A pair of operands promoted to a common numeric type, or `None` if either
+/// side is a `Bool` and the pair cannot be used arithmetically.
+enum NumPair {
+    Ints(i64, i64),
+    Floats(f64, f64),
+}
+
+fn numeric_pair(lhs: Value, rhs: Value) -> Option<NumPair> {
+    match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => Some(NumPair::Ints(a, b)),
+        (Value::Int(a), Value::Float(b)) => Some(NumPair::Floats(a as f64, b)),
+        (Value::Float(a), Value::Int(b)) => Some(NumPair::Floats(a, b as f64)),
+        (Value::Float(a), Value::Float(b)) => Some(NumPair::Floats(a, b)),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum EvalError {
+    UnexpectedEof,
+    IllegalToken(char, usize),
+    UnexpectedToken(Token, usize),
+    UnbalancedParen(usize),
+    NumberOverflow(usize),
+    DivByZero(usize),
+    NegativeExponent(usize),
+    ArithmeticOverflow(usize),
+    InvalidShiftAmount(usize),
+    TrailingTokens(usize),
+    UndefinedVariable(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnexpectedEof => write!(f, "unexpected end of input"),
+            EvalError::IllegalToken(c, pos) => {
+                write!(f, "illegal character '{}' at position {}", c, pos)
+            }
+            EvalError::UnexpectedToken(token, pos) => {
+                write!(f, "unexpected token {:?} at position {}", token, pos)
+            }
+            EvalError::UnbalancedParen(pos) => {
+                write!(f, "unbalanced parenthesis opened at position {}", pos)
+            }
+            EvalError::NumberOverflow(pos) => {
+                write!(f, "number starting at position {} is malformed or overflows i64", pos)
+            }
+            EvalError::DivByZero(pos) => {
+                write!(f, "division by zero at position {}", pos)
+            }
+            EvalError::NegativeExponent(pos) => {
+                write!(f, "negative exponent for '^' at position {} is not supported for integers", pos)
+            }
+            EvalError::ArithmeticOverflow(pos) => {
+                write!(f, "arithmetic overflow at position {}", pos)
+            }
+            EvalError::InvalidShiftAmount(pos) => {
+                write!(f, "shift amount for the operator at position {} must be in the range 0..64", pos)
+            }
+            EvalError::TrailingTokens(pos) => {
+                write!(f, "unexpected trailing input at position {}", pos)
+            }
+            EvalError::UndefinedVariable(name) => write!(f, "undefined variable '{}'", name),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
 struct Tokenizer {
     chars: Peekable<IntoIter<char>>,
+    pos: usize,
 }
 
 impl Tokenizer {
     pub fn new(source: Vec<char>) -> Tokenizer {
         Tokenizer {
             chars: source.into_iter().peekable(),
+            pos: 0,
         }
     }
 
-    fn parse_num(&mut self, first: char) -> Token {
+    fn parse_num(&mut self, first: char, start: usize) -> Result<Token, EvalError> {
         let mut buf = String::from(first);
-        while let Some(n) = self.chars.peek() {
+        let mut is_float = false;
+        let mut has_exponent = false;
+        while let Some(n) = self.chars.peek().copied() {
             match n {
                 c @ '0'..='9' => {
-                    buf.push(*c);
+                    buf.push(c);
+                    self.chars.next();
+                    self.pos += 1;
+                }
+                '.' if !is_float && !has_exponent => {
+                    is_float = true;
+                    buf.push('.');
                     self.chars.next();
+                    self.pos += 1;
+                }
+                c @ ('e' | 'E') if !has_exponent => {
+                    is_float = true;
+                    has_exponent = true;
+                    buf.push(c);
+                    self.chars.next();
+                    self.pos += 1;
+                    if let Some(sign @ ('+' | '-')) = self.chars.peek().copied() {
+                        buf.push(sign);
+                        self.chars.next();
+                        self.pos += 1;
+                    }
                 }
                 _ => break,
             }
         }
-        Token::Int(buf.parse::<i64>().unwrap())
+        if is_float {
+            buf.parse::<f64>()
+                .map(Token::Float)
+                .map_err(|_| EvalError::NumberOverflow(start))
+        } else {
+            buf.parse::<i64>()
+                .map(Token::Int)
+                .map_err(|_| EvalError::NumberOverflow(start))
+        }
+    }
+
+    fn parse_ident(&mut self, first: char) -> Token {
+        let mut buf = String::from(first);
+        while let Some(&c) = self.chars.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                buf.push(c);
+                self.chars.next();
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        Token::Ident(buf)
     }
 }
 
@@ -48,22 +217,70 @@ impl IntoTokens for Chars<'_> {
 }
 
 impl Iterator for Tokenizer {
-    type Item = Token;
+    type Item = Result<TokenAt, EvalError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         while let Some(c) = self.chars.next() {
+            let start = self.pos;
+            self.pos += 1;
             use self::Token::*;
-            let next = match c {
-                '+' => Some(Add),
-                '*' => Some(Mul),
-                '(' => Some(LParen),
-                ')' => Some(RParen),
-                first @ '0'..='9' => Some(self.parse_num(first)),
-                _ => None,
+            let token = match c {
+                ' ' | '\t' | '\n' | '\r' => continue,
+                '+' => Ok(Add),
+                '-' => Ok(Sub),
+                '*' => Ok(Mul),
+                '/' => Ok(Div),
+                '%' => Ok(Mod),
+                '^' => Ok(Pow),
+                '(' => Ok(LParen),
+                ')' => Ok(RParen),
+                '=' if self.chars.peek() == Some(&'=') => {
+                    self.chars.next();
+                    self.pos += 1;
+                    Ok(Eq)
+                }
+                '!' if self.chars.peek() == Some(&'=') => {
+                    self.chars.next();
+                    self.pos += 1;
+                    Ok(Ne)
+                }
+                '<' if self.chars.peek() == Some(&'=') => {
+                    self.chars.next();
+                    self.pos += 1;
+                    Ok(Le)
+                }
+                '<' if self.chars.peek() == Some(&'<') => {
+                    self.chars.next();
+                    self.pos += 1;
+                    Ok(Shl)
+                }
+                '<' => Ok(Lt),
+                '>' if self.chars.peek() == Some(&'=') => {
+                    self.chars.next();
+                    self.pos += 1;
+                    Ok(Ge)
+                }
+                '>' if self.chars.peek() == Some(&'>') => {
+                    self.chars.next();
+                    self.pos += 1;
+                    Ok(Shr)
+                }
+                '>' => Ok(Gt),
+                '&' => Ok(BitAnd),
+                '|' => Ok(BitOr),
+                '~' if self.chars.peek() == Some(&'^') => {
+                    self.chars.next();
+                    self.pos += 1;
+                    Ok(BitXor)
+                }
+                '=' => Ok(Assign),
+                first @ '0'..='9' => self.parse_num(first, start),
+                first if first.is_ascii_alphabetic() || first == '_' => {
+                    Ok(self.parse_ident(first))
+                }
+                other => Err(EvalError::IllegalToken(other, start)),
             };
-            if next.is_some() {
-                return next;
-            }
+            return Some(token.map(|token| TokenAt { token, pos: start }));
         }
         None
     }
@@ -71,134 +288,308 @@ impl Iterator for Tokenizer {
 
 use self::Token::*;
 
-fn eval(expression: &str) -> i64 {
-    _eval(&mut expression.chars().into_tokens().peekable())
+/// Binding powers for each operator, `(left, right)`. A `None` result means
+/// the token cannot appear as a binary operator. Left-associative operators
+/// bind their right-hand side one tighter than their left (`right = left +
+/// 1`); the right-associative `^` binds its right-hand side one looser than
+/// its left (`right = left - 1`).
+type BindingPower = fn(&Token) -> Option<(u8, u8)>;
+
+/// Part 1: `+`/`-` and `*`/`/`/`%` all share a precedence tier. Comparisons
+/// bind loosest, then the bitwise operators, then arithmetic, then `^`
+/// (right-associative) tightest of all.
+fn equal_precedence(token: &Token) -> Option<(u8, u8)> {
+    match token {
+        Eq | Ne | Lt | Le | Gt | Ge => Some((1, 2)),
+        BitAnd | BitOr | BitXor | Shl | Shr => Some((3, 4)),
+        Add | Sub | Mul | Div | Mod => Some((5, 6)),
+        Pow => Some((9, 8)),
+        _ => None,
+    }
 }
 
-fn _eval(tokens: &mut Peekable<Tokenizer>) -> i64 {
-    let mut current = eval_operand(tokens);
-    while tokens.peek().is_some() {
-        current = eval_operation(current, tokens);
+/// Part 2: `+`/`-` bind tighter than `*`/`/`/`%`. Comparisons still bind
+/// loosest, then the bitwise operators, then arithmetic, then `^`
+/// (right-associative) tightest of all.
+fn addition_first_precedence(token: &Token) -> Option<(u8, u8)> {
+    match token {
+        Eq | Ne | Lt | Le | Gt | Ge => Some((1, 2)),
+        BitAnd | BitOr | BitXor | Shl | Shr => Some((3, 4)),
+        Mul | Div | Mod => Some((5, 6)),
+        Add | Sub => Some((7, 8)),
+        Pow => Some((11, 10)),
+        _ => None,
     }
-    current
 }
 
-fn eval_operand(tokens: &mut Peekable<Tokenizer>) -> i64 {
-    match tokens
-        .next()
-        .expect("unexpected end of tokens evaluating operand")
-    {
-        Int(n) => n,
-        LParen => eval_subexpr(tokens),
-        other => panic!("illegal token in operand {:?}", other),
+fn eval(expression: &str, env: &mut Environment) -> Result<Value, EvalError> {
+    let mut tokens = expression.chars().into_tokens().peekable();
+    let value = parse_expr(&mut tokens, 0, equal_precedence, env)?;
+    expect_exhausted(&mut tokens)?;
+    Ok(value)
+}
+
+fn fval(line: &str, env: &mut Environment) -> Result<Value, EvalError> {
+    let mut tokens = line.chars().into_tokens().peekable();
+    let value = parse_expr(&mut tokens, 0, addition_first_precedence, env)?;
+    expect_exhausted(&mut tokens)?;
+    Ok(value)
+}
+
+/// Confirms the tokenizer has nothing left to yield, so trailing garbage
+/// after a complete expression (`1 + 2 3`, `5 )`) is reported instead of
+/// silently discarded.
+fn expect_exhausted(tokens: &mut Peekable<Tokenizer>) -> Result<(), EvalError> {
+    match tokens.next() {
+        None => Ok(()),
+        Some(Ok(tok_at)) => Err(EvalError::TrailingTokens(tok_at.pos)),
+        Some(Err(err)) => Err(err),
     }
 }
 
-fn eval_subexpr(tokens: &mut Peekable<Tokenizer>) -> i64 {
-    let mut current = eval_operand(tokens);
-    while let Some(peeked) = tokens.peek() {
-        match peeked {
-            RParen => {
+/// Precedence-climbing (Pratt) parser shared by `eval` and `fval`. Which
+/// operators bind tighter than which is entirely determined by `bp`, so the
+/// two evaluators differ only in which `BindingPower` function they pass in.
+fn parse_expr(
+    tokens: &mut Peekable<Tokenizer>,
+    min_bp: u8,
+    bp: BindingPower,
+    env: &mut Environment,
+) -> Result<Value, EvalError> {
+    let mut lhs = parse_operand(tokens, bp, env)?;
+
+    loop {
+        let op_at = match tokens.peek() {
+            Some(Ok(tok_at)) => tok_at.clone(),
+            Some(Err(_)) => return Err(next_err(tokens)),
+            None => break,
+        };
+        let (_, right_bp) = match bp(&op_at.token) {
+            Some(powers) if powers.0 >= min_bp => powers,
+            _ => break,
+        };
+        tokens.next();
+        let rhs = parse_expr(tokens, right_bp, bp, env)?;
+        lhs = apply_op(op_at, lhs, rhs)?;
+    }
+
+    Ok(lhs)
+}
+
+fn parse_operand(
+    tokens: &mut Peekable<Tokenizer>,
+    bp: BindingPower,
+    env: &mut Environment,
+) -> Result<Value, EvalError> {
+    let tok_at = match tokens.next() {
+        Some(Ok(tok_at)) => tok_at,
+        Some(Err(err)) => return Err(err),
+        None => return Err(EvalError::UnexpectedEof),
+    };
+    let pos = tok_at.pos;
+
+    match tok_at.token {
+        Int(n) => Ok(Value::Int(n)),
+        Float(n) => Ok(Value::Float(n)),
+        Ident(name) => {
+            if matches!(
+                tokens.peek(),
+                Some(Ok(TokenAt { token: Assign, .. }))
+            ) {
                 tokens.next();
-                return current;
+                let value = parse_expr(tokens, 0, bp, env)?;
+                env.insert(name, value);
+                Ok(value)
+            } else {
+                env.get(&name)
+                    .copied()
+                    .ok_or(EvalError::UndefinedVariable(name))
             }
-            _ => current = eval_operation(current, tokens),
         }
+        Sub => match parse_operand(tokens, bp, env)? {
+            Value::Int(n) => Ok(Value::Int(-n)),
+            Value::Float(n) => Ok(Value::Float(-n)),
+            Value::Bool(_) => Err(EvalError::UnexpectedToken(Token::Sub, pos)),
+        },
+        LParen => {
+            let value = parse_expr(tokens, 0, bp, env)?;
+            match tokens.next() {
+                Some(Ok(TokenAt { token: RParen, .. })) => Ok(value),
+                Some(Ok(other)) => Err(EvalError::UnexpectedToken(other.token, other.pos)),
+                Some(Err(err)) => Err(err),
+                None => Err(EvalError::UnbalancedParen(pos)),
+            }
+        }
+        other => Err(EvalError::UnexpectedToken(other, pos)),
     }
-    current
 }
 
-fn eval_operation(loper: i64, tokens: &mut Peekable<Tokenizer>) -> i64 {
-    let operation = tokens
-        .next()
-        .expect("unexpected end of tokens evaluating operation");
-    let roper = eval_operand(tokens);
-
-    match operation {
-        Add => loper + roper,
-        Mul => loper * roper,
+fn apply_op(operation: TokenAt, loper: Value, roper: Value) -> Result<Value, EvalError> {
+    match &operation.token {
+        Add | Sub | Mul | Div | Mod | Pow => apply_arithmetic(operation, loper, roper),
+        Eq | Ne | Lt | Le | Gt | Ge => apply_comparison(operation, loper, roper),
+        BitAnd | BitOr | BitXor | Shl | Shr => apply_bitwise(operation, loper, roper),
         other => panic!("{:?} is not an operation", other),
     }
 }
 
-//fval because f comes after e...
-fn fval(line: &str) -> i64 {
-    _fval(&mut line.chars().into_tokens().peekable())
+fn apply_bitwise(operation: TokenAt, loper: Value, roper: Value) -> Result<Value, EvalError> {
+    let (a, b) = match (loper, roper) {
+        (Value::Int(a), Value::Int(b)) => (a, b),
+        _ => return Err(EvalError::UnexpectedToken(operation.token, operation.pos)),
+    };
+    match operation.token {
+        Shl | Shr if !(0..64).contains(&b) => Err(EvalError::InvalidShiftAmount(operation.pos)),
+        BitAnd => Ok(Value::Int(a & b)),
+        BitOr => Ok(Value::Int(a | b)),
+        BitXor => Ok(Value::Int(a ^ b)),
+        Shl => Ok(Value::Int(a << b)),
+        Shr => Ok(Value::Int(a >> b)),
+        other => panic!("{:?} is not a bitwise operation", other),
+    }
 }
 
-fn _fval(tokens: &mut Peekable<Tokenizer>) -> i64 {
-    let mut current = fval_loperand(tokens);
-    while tokens.peek().is_some() {
-        current = fval_operation(current, tokens);
+fn apply_arithmetic(operation: TokenAt, loper: Value, roper: Value) -> Result<Value, EvalError> {
+    let token = operation.token.clone();
+    let pair = numeric_pair(loper, roper)
+        .ok_or_else(|| EvalError::UnexpectedToken(token.clone(), operation.pos))?;
+    match (token, pair) {
+        (Add, NumPair::Ints(a, b)) => a
+            .checked_add(b)
+            .map(Value::Int)
+            .ok_or(EvalError::ArithmeticOverflow(operation.pos)),
+        (Add, NumPair::Floats(a, b)) => Ok(Value::Float(a + b)),
+        (Sub, NumPair::Ints(a, b)) => a
+            .checked_sub(b)
+            .map(Value::Int)
+            .ok_or(EvalError::ArithmeticOverflow(operation.pos)),
+        (Sub, NumPair::Floats(a, b)) => Ok(Value::Float(a - b)),
+        (Mul, NumPair::Ints(a, b)) => a
+            .checked_mul(b)
+            .map(Value::Int)
+            .ok_or(EvalError::ArithmeticOverflow(operation.pos)),
+        (Mul, NumPair::Floats(a, b)) => Ok(Value::Float(a * b)),
+        (Div, NumPair::Ints(_, 0)) => Err(EvalError::DivByZero(operation.pos)),
+        (Div, NumPair::Ints(a, b)) => Ok(Value::Int(a / b)),
+        (Div, NumPair::Floats(a, b)) => Ok(Value::Float(a / b)),
+        (Mod, NumPair::Ints(_, 0)) => Err(EvalError::DivByZero(operation.pos)),
+        (Mod, NumPair::Ints(a, b)) => Ok(Value::Int(a % b)),
+        (Mod, NumPair::Floats(a, b)) => Ok(Value::Float(a % b)),
+        (Pow, NumPair::Ints(_, b)) if b < 0 => Err(EvalError::NegativeExponent(operation.pos)),
+        (Pow, NumPair::Ints(a, b)) => a
+            .checked_pow(b as u32)
+            .map(Value::Int)
+            .ok_or(EvalError::ArithmeticOverflow(operation.pos)),
+        (Pow, NumPair::Floats(a, b)) => Ok(Value::Float(a.powf(b))),
+        (other, _) => panic!("{:?} is not an arithmetic operation", other),
     }
-    current
 }
 
-fn fval_subexpr(tokens: &mut Peekable<Tokenizer>) -> i64 {
-    let mut current = fval_loperand(tokens);
-    while let Some(peeked) = tokens.peek() {
-        match peeked {
-            RParen => {
-                tokens.next();
-                return current;
-            }
-            _ => current = fval_operation(current, tokens),
+fn apply_comparison(operation: TokenAt, loper: Value, roper: Value) -> Result<Value, EvalError> {
+    let token = operation.token.clone();
+    let ordering = match (loper, roper) {
+        (Value::Bool(a), Value::Bool(b)) => {
+            return match token {
+                Eq => Ok(Value::Bool(a == b)),
+                Ne => Ok(Value::Bool(a != b)),
+                _ => Err(EvalError::UnexpectedToken(token, operation.pos)),
+            };
         }
-    }
-    current
+        _ => match numeric_pair(loper, roper) {
+            Some(NumPair::Ints(a, b)) => a.cmp(&b),
+            Some(NumPair::Floats(a, b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            None => return Err(EvalError::UnexpectedToken(token, operation.pos)),
+        },
+    };
+    Ok(Value::Bool(match token {
+        Eq => ordering == Ordering::Equal,
+        Ne => ordering != Ordering::Equal,
+        Lt => ordering == Ordering::Less,
+        Le => ordering != Ordering::Greater,
+        Gt => ordering == Ordering::Greater,
+        Ge => ordering != Ordering::Less,
+        other => panic!("{:?} is not a comparison", other),
+    }))
 }
 
-fn fval_loperand(tokens: &mut Peekable<Tokenizer>) -> i64 {
-    match tokens
-        .next()
-        .expect("unexpected end of tokens evaluating operand")
-    {
-        Int(n) => n,
-        LParen => fval_subexpr(tokens),
-        other => panic!("illegal token in operand {:?}", other),
+/// Pulls the error out of a `Peekable<Tokenizer>` already known (via `peek`)
+/// to be sitting on an `Err`.
+fn next_err(tokens: &mut Peekable<Tokenizer>) -> EvalError {
+    match tokens.next() {
+        Some(Err(err)) => err,
+        _ => unreachable!("next_err called without a peeked error"),
     }
 }
 
-fn fval_roperand(tokens: &mut Peekable<Tokenizer>) -> i64 {
-    let roper = match tokens
-        .next()
-        .expect("unexpected end of tokens evaluating operand")
-    {
-        Int(n) => n,
-        LParen => fval_subexpr(tokens),
-        other => panic!("illegal token in operand {:?}", other),
+/// Sums the numeric results of running `evaluator` over every line of
+/// `input`, each line evaluated against its own fresh `Environment`. Lines
+/// that evaluate to a `Bool` are not numeric and are skipped.
+fn sum_numeric(
+    input: &str,
+    evaluator: fn(&str, &mut Environment) -> Result<Value, EvalError>,
+) -> Value {
+    let add = TokenAt {
+        token: Token::Add,
+        pos: 0,
     };
-    if matches!(tokens.peek(), Some(Add)) {
-        tokens.next();
-        fval_addition(roper, tokens)
-    } else {
-        roper
+    let mut total = Value::Int(0);
+    for (line_no, line) in input.lines().enumerate() {
+        let mut env = Environment::new();
+        match evaluator(line, &mut env) {
+            Ok(Value::Bool(_)) => {}
+            Ok(value) => {
+                total = match apply_op(add.clone(), total, value) {
+                    Ok(sum) => sum,
+                    Err(err) => {
+                        eprintln!("error adding line {} to the total: {}", line_no + 1, err);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            Err(err) => {
+                eprintln!("error on line {}: {}", line_no + 1, err);
+                std::process::exit(1);
+            }
+        }
     }
+    total
 }
 
-fn fval_addition(loper: i64, tokens: &mut Peekable<Tokenizer>) -> i64 {
-    loper + fval_roperand(tokens)
-}
-
-fn fval_operation(loper: i64, tokens: &mut Peekable<Tokenizer>) -> i64 {
-    let operation = tokens
-        .next()
-        .expect("unexpected end of tokens evaluating operation");
-    let roper = fval_roperand(tokens);
-
-    match operation {
-        Add => loper + roper,
-        Mul => loper * roper,
-        other => panic!("{:?} is not an operation", other),
+/// Reads expressions from stdin against a persistent `Environment`, printing
+/// each result, so assignments made on one line are visible on later ones.
+fn run_repl() {
+    use std::io::{self, BufRead, Write};
+
+    let mut env = Environment::new();
+    let stdin = io::stdin();
+    print!("> ");
+    io::stdout().flush().ok();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        match eval(&line, &mut env) {
+            Ok(value) => println!("{}", value),
+            Err(err) => eprintln!("error: {}", err),
+        }
+        print!("> ");
+        io::stdout().flush().ok();
     }
 }
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--repl") {
+        run_repl();
+        return;
+    }
+
     let input = include_str!("../input.txt");
-    let part_1: i64 = input.lines().map(eval).sum();
+
+    let part_1 = sum_numeric(input, eval);
     println!("The answer to part 1 is {}", part_1);
 
-    let part_2: i64 = input.lines().map(fval).sum();
+    let part_2 = sum_numeric(input, fval);
     println!("The answer to part 2 is {}", part_2);
 }
 
@@ -206,92 +597,292 @@ fn main() {
 mod tests {
     use super::*;
 
+    fn eval_fresh(expression: &str) -> Result<Value, EvalError> {
+        eval(expression, &mut Environment::new())
+    }
+
+    fn fval_fresh(line: &str) -> Result<Value, EvalError> {
+        fval(line, &mut Environment::new())
+    }
+
     #[test]
     fn tokenizer_produces_correct_tokens() {
         let mut tokenizer = "1()+*".chars().into_tokens();
-        assert!(matches!(tokenizer.next(), Some(Token::Int(1))));
-        assert!(matches!(tokenizer.next(), Some(Token::LParen)));
-        assert!(matches!(tokenizer.next(), Some(Token::RParen)));
-        assert!(matches!(tokenizer.next(), Some(Token::Add)));
-        assert!(matches!(tokenizer.next(), Some(Token::Mul)));
+        assert!(matches!(
+            tokenizer.next(),
+            Some(Ok(TokenAt {
+                token: Token::Int(1),
+                ..
+            }))
+        ));
+        assert!(matches!(
+            tokenizer.next(),
+            Some(Ok(TokenAt {
+                token: Token::LParen,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            tokenizer.next(),
+            Some(Ok(TokenAt {
+                token: Token::RParen,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            tokenizer.next(),
+            Some(Ok(TokenAt {
+                token: Token::Add,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            tokenizer.next(),
+            Some(Ok(TokenAt {
+                token: Token::Mul,
+                ..
+            }))
+        ));
         assert!(matches!(tokenizer.next(), None));
     }
 
+    #[test]
+    fn tokenizer_reports_illegal_characters() {
+        let mut tokenizer = "1@2".chars().into_tokens();
+        assert!(matches!(
+            tokenizer.next(),
+            Some(Ok(TokenAt {
+                token: Token::Int(1),
+                ..
+            }))
+        ));
+        assert_eq!(
+            Some(Err(EvalError::IllegalToken('@', 1))),
+            tokenizer.next()
+        );
+    }
+
     #[test]
     fn it_can_eval_a_literal_expression() {
-        let result = eval("123");
-        assert_eq!(123, result);
+        let result = eval_fresh("123").unwrap();
+        assert_eq!(Value::Int(123), result);
     }
 
     #[test]
     fn it_can_eval_an_addition() {
-        let result = eval("10 + 11");
-        assert_eq!(21, result);
+        let result = eval_fresh("10 + 11").unwrap();
+        assert_eq!(Value::Int(21), result);
     }
 
     #[test]
     fn it_can_eval_a_multiplication() {
-        assert_eq!(110, eval("10 * 11"));
+        assert_eq!(Value::Int(110), eval_fresh("10 * 11").unwrap());
     }
 
     #[test]
     fn it_can_eval_a_chained_expression() {
-        assert_eq!(105, eval("10 + 11 * 5"))
+        assert_eq!(Value::Int(105), eval_fresh("10 + 11 * 5").unwrap())
     }
 
     #[test]
     fn it_can_eval_simple_subexpressions() {
-        let result = eval("(10) + (11)");
-        assert_eq!(21, result);
+        let result = eval_fresh("(10) + (11)").unwrap();
+        assert_eq!(Value::Int(21), result);
     }
 
     #[test]
     fn it_can_eval_subexpressions_with_arithmatic() {
-        let result = eval("(10 + 10) + (11 * 11)");
-        assert_eq!(141, result);
+        let result = eval_fresh("(10 + 10) + (11 * 11)").unwrap();
+        assert_eq!(Value::Int(141), result);
     }
 
     #[test]
     fn examples_from_question_parse_correctly() {
-        assert_eq!(26, eval("2 * 3 + (4 * 5)"));
-        assert_eq!(437, eval("5 + (8 * 3 + 9 + 3 * 4 * 3)"));
-        assert_eq!(12240, eval("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))"));
+        assert_eq!(Value::Int(26), eval_fresh("2 * 3 + (4 * 5)").unwrap());
+        assert_eq!(Value::Int(437), eval_fresh("5 + (8 * 3 + 9 + 3 * 4 * 3)").unwrap());
+        assert_eq!(
+            Value::Int(12240),
+            eval_fresh("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))").unwrap()
+        );
         assert_eq!(
-            13632,
-            eval("((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2")
+            Value::Int(13632),
+            eval_fresh("((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2").unwrap()
         );
     }
 
     #[test]
     fn it_can_advanced_eval_literal_expressions() {
-        assert_eq!(123, fval("123"));
+        assert_eq!(Value::Int(123), fval_fresh("123").unwrap());
     }
 
     #[test]
     fn it_can_advanced_eval_addition() {
-        assert_eq!(21, fval("10 + 11"));
+        assert_eq!(Value::Int(21), fval_fresh("10 + 11").unwrap());
     }
 
     #[test]
     fn it_can_advanced_eval_multiplication() {
-        assert_eq!(110, fval("10 * 11"));
+        assert_eq!(Value::Int(110), fval_fresh("10 * 11").unwrap());
     }
 
     #[test]
     fn it_can_advanced_eval_chained_operations() {
-        assert_eq!(230, fval("10 * 11 + 12"));
+        assert_eq!(Value::Int(230), fval_fresh("10 * 11 + 12").unwrap());
     }
 
     #[test]
     fn it_can_advanced_eval_examples_from_the_question() {
-        assert_eq!(231, fval("1 + 2 * 3 + 4 * 5 + 6"));
-        assert_eq!(51, fval("1 + (2 * 3) + (4 * (5 + 6))"));
-        assert_eq!(46, fval("2 * 3 + (4 * 5)"));
-        assert_eq!(1445, fval("5 + (8 * 3 + 9 + 3 * 4 * 3)"));
-        assert_eq!(669060, fval("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))"));
+        assert_eq!(Value::Int(231), fval_fresh("1 + 2 * 3 + 4 * 5 + 6").unwrap());
+        assert_eq!(Value::Int(51), fval_fresh("1 + (2 * 3) + (4 * (5 + 6))").unwrap());
+        assert_eq!(Value::Int(46), fval_fresh("2 * 3 + (4 * 5)").unwrap());
+        assert_eq!(Value::Int(1445), fval_fresh("5 + (8 * 3 + 9 + 3 * 4 * 3)").unwrap());
+        assert_eq!(
+            Value::Int(669060),
+            fval_fresh("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))").unwrap()
+        );
         assert_eq!(
-            23340,
-            fval("((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2")
+            Value::Int(23340),
+            fval_fresh("((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2").unwrap()
         );
     }
+
+    #[test]
+    fn it_can_eval_subtraction_division_modulo_and_exponent() {
+        assert_eq!(Value::Int(5), eval_fresh("10 - 5").unwrap());
+        assert_eq!(Value::Int(4), eval_fresh("20 / 5").unwrap());
+        assert_eq!(Value::Int(2), eval_fresh("17 % 5").unwrap());
+        assert_eq!(Value::Int(8), eval_fresh("2 ^ 3").unwrap());
+    }
+
+    #[test]
+    fn it_can_eval_unary_minus() {
+        assert_eq!(Value::Int(-5), eval_fresh("-5").unwrap());
+        assert_eq!(Value::Int(3), eval_fresh("10 + -7").unwrap());
+    }
+
+    #[test]
+    fn pow_is_right_associative() {
+        assert_eq!(Value::Int(512), eval_fresh("2 ^ (3 ^ 2)").unwrap());
+        assert_eq!(Value::Int(512), eval_fresh("2 ^ 3 ^ 2").unwrap());
+    }
+
+    #[test]
+    fn it_reports_division_and_modulo_by_zero() {
+        assert_eq!(Err(EvalError::DivByZero(3)), eval_fresh("10 / 0"));
+        assert_eq!(Err(EvalError::DivByZero(3)), eval_fresh("10 % 0"));
+    }
+
+    #[test]
+    fn it_reports_negative_exponents() {
+        assert_eq!(Err(EvalError::NegativeExponent(2)), eval_fresh("2 ^ -1"));
+    }
+
+    #[test]
+    fn it_reports_arithmetic_overflow() {
+        assert_eq!(Err(EvalError::ArithmeticOverflow(2)), eval_fresh("2 ^ 100"));
+        assert_eq!(
+            Err(EvalError::ArithmeticOverflow(20)),
+            eval_fresh("9223372036854775807 + 1")
+        );
+        assert_eq!(
+            Err(EvalError::ArithmeticOverflow(21)),
+            eval_fresh("-9223372036854775807 - 2")
+        );
+        assert_eq!(
+            Err(EvalError::ArithmeticOverflow(20)),
+            eval_fresh("9223372036854775807 * 2")
+        );
+    }
+
+    #[test]
+    fn it_reports_unbalanced_parens() {
+        assert_eq!(Err(EvalError::UnbalancedParen(0)), eval_fresh("(1 + 2"));
+    }
+
+    #[test]
+    fn it_reports_unexpected_end_of_input() {
+        assert_eq!(Err(EvalError::UnexpectedEof), eval_fresh("1 +"));
+    }
+
+    #[test]
+    fn it_reports_trailing_tokens() {
+        assert_eq!(Err(EvalError::TrailingTokens(6)), eval_fresh("1 + 2 3 4"));
+        assert_eq!(Err(EvalError::TrailingTokens(7)), eval_fresh("(1 + 2))"));
+        assert_eq!(Err(EvalError::TrailingTokens(2)), eval_fresh("5 )"));
+        let mut env = Environment::new();
+        assert_eq!(
+            Err(EvalError::TrailingTokens(6)),
+            eval("x = 5 10", &mut env)
+        );
+    }
+
+    #[test]
+    fn it_can_eval_floats_with_numeric_promotion() {
+        assert_eq!(Value::Float(4.0), eval_fresh("1.5 + 2.5").unwrap());
+        assert_eq!(Value::Float(3.5), eval_fresh("1 + 2.5").unwrap());
+        assert_eq!(Value::Float(1.5e2), eval_fresh("1.5e2").unwrap());
+    }
+
+    #[test]
+    fn it_can_eval_comparisons() {
+        assert_eq!(Value::Bool(true), eval_fresh("3 < 5").unwrap());
+        assert_eq!(Value::Bool(true), eval_fresh("3 == 3.0").unwrap());
+        assert_eq!(Value::Bool(false), eval_fresh("3 != 3.0").unwrap());
+        assert_eq!(Value::Bool(true), eval_fresh("(3 < 5) == (2 < 4)").unwrap());
+    }
+
+    #[test]
+    fn it_can_assign_and_read_back_a_variable() {
+        let mut env = Environment::new();
+        assert_eq!(Value::Int(5), eval("x = 2 + 3", &mut env).unwrap());
+        assert_eq!(Value::Int(20), eval("x * 4", &mut env).unwrap());
+    }
+
+    #[test]
+    fn assignment_is_an_expression() {
+        let mut env = Environment::new();
+        assert_eq!(Value::Int(15), eval("2 * (x = 5) + 5", &mut env).unwrap());
+        assert_eq!(Value::Int(5), *env.get("x").unwrap());
+    }
+
+    #[test]
+    fn it_reports_undefined_variables() {
+        assert_eq!(
+            Err(EvalError::UndefinedVariable("y".to_string())),
+            eval_fresh("y + 1")
+        );
+    }
+
+    #[test]
+    fn it_can_eval_bitwise_operators() {
+        assert_eq!(Value::Int(2), eval_fresh("6 & 3").unwrap());
+        assert_eq!(Value::Int(7), eval_fresh("6 | 3").unwrap());
+        assert_eq!(Value::Int(5), eval_fresh("6 ~^ 3").unwrap());
+        assert_eq!(Value::Int(24), eval_fresh("3 << 3").unwrap());
+        assert_eq!(Value::Int(3), eval_fresh("24 >> 3").unwrap());
+    }
+
+    #[test]
+    fn bitwise_operators_bind_looser_than_arithmetic() {
+        assert_eq!(Value::Int(4), eval_fresh("6 & 3 + 1").unwrap());
+    }
+
+    #[test]
+    fn it_reports_bitwise_operators_applied_to_non_integers() {
+        assert_eq!(
+            Err(EvalError::UnexpectedToken(Token::BitAnd, 4)),
+            eval_fresh("1.5 & 3")
+        );
+        assert_eq!(
+            Err(EvalError::UnexpectedToken(Token::BitOr, 8)),
+            eval_fresh("(1 < 2) | 3")
+        );
+    }
+
+    #[test]
+    fn it_reports_invalid_shift_amounts() {
+        assert_eq!(Err(EvalError::InvalidShiftAmount(2)), eval_fresh("3 << -1"));
+        assert_eq!(Err(EvalError::InvalidShiftAmount(2)), eval_fresh("3 << 64"));
+        assert_eq!(Err(EvalError::InvalidShiftAmount(2)), eval_fresh("3 >> -1"));
+    }
 }